@@ -1,13 +1,559 @@
 use atomic::AtomicUsize;
 
 use rayon::{prelude::*, Scope};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
+use std::io::Read;
 use std::io::Result;
+use std::path::Path;
 use std::path::PathBuf;
-use std::sync::{atomic, RwLock};
+use std::str::FromStr;
+use std::sync::{atomic, Mutex, RwLock};
+use std::time::SystemTime;
 use structopt::StructOpt;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Size of each chunk read while hashing a file. Used for both the cheap partial hash and
+/// the streaming full hash so neither ever has to load an entire file into memory.
+const BLOCK_SIZE: usize = 4096;
+
+/// Hash algorithm used to compare file contents. `Sha1` and `Blake3` produce wide enough
+/// digests (160 and 256 bits) that a same-digest collision within a size bucket can be
+/// trusted outright. `Xxh3` is fast but only 64 bits wide, which is a real collision risk
+/// for a tool whose job is permanent deletion; a full byte-for-byte comparison is performed
+/// before deleting anything whose "duplicate" status rests on an `Xxh3` match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum HashAlgo {
+    Sha1,
+    Blake3,
+    Xxh3,
+}
+
+impl FromStr for HashAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(HashAlgo::Sha1),
+            "blake3" => Ok(HashAlgo::Blake3),
+            "xxh3" => Ok(HashAlgo::Xxh3),
+            other => Err(format!("unknown hash algorithm: {}", other)),
+        }
+    }
+}
+
+/// Thin dispatch wrapper so the hashing loop doesn't need to care which algorithm was
+/// selected.
+enum Hasher {
+    Sha1(Sha1),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgo::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgo::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+            Hasher::Xxh3(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+            // Only 8 bytes wide; callers must not treat an Xxh3 match as final proof of
+            // equality on its own (see `files_equal` and its call site).
+            Hasher::Xxh3(h) => h.digest().to_le_bytes().to_vec(),
+        }
+    }
+}
+
+/// Whether a file's cached digest covers only its first block or its entire contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashMode {
+    Partial,
+    Full,
+}
+
+/// Previously computed digests for a path, recorded alongside the file metadata and hash
+/// algorithm they were valid for so a later run can tell whether the file (or the selected
+/// `--hash`) has changed since. Partial and full digests are tracked independently: a file
+/// is always probed `Partial` first and only promoted to `Full` if it survives that pass, so
+/// a single `mode` field would have each probe evict the other's cached result every time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    len: u64,
+    modified: SystemTime,
+    algo: HashAlgo,
+    partial_digest: Option<Vec<u8>>,
+    full_digest: Option<Vec<u8>>,
+}
+
+/// On-disk cache of file digests, keyed by canonical path. Loaded once at startup and saved
+/// once on exit so re-running on an unchanged tree doesn't re-read any file contents.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HashCache {
+    entries: HashMap<PathBuf, CachedHash>,
+}
+
+impl HashCache {
+    fn load(path: &Path) -> HashCache {
+        fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) {
+        if let Ok(bytes) = serde_json::to_vec(self) {
+            let _ = fs::write(path, bytes);
+        }
+    }
+}
+
+/// A candidate duplicate, tagged with the hash computed for it so far. `digest` starts out
+/// partial and is only recomputed in `Full` mode for entries that survive the partial pass.
+/// `dev_ino` is the device/inode pair backing the file on Unix, used to recognize files that
+/// are already hard-linked together so they aren't treated as separate duplicates.
+struct HashedEntry<'a> {
+    entry: &'a fs::DirEntry,
+    digest: Vec<u8>,
+    dev_ino: Option<(u64, u64)>,
+}
+
+/// Returns the `(device, inode)` pair backing `path` on Unix, or `None` on other platforms.
+#[cfg(unix)]
+fn dev_ino(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn dev_ino(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Hashes `path` with a streaming reader, never loading the whole file into memory. In
+/// `Partial` mode only the first `BLOCK_SIZE` bytes are hashed; in `Full` mode the file is
+/// read to the end in `BLOCK_SIZE` chunks.
+fn hash_file(path: &Path, mode: HashMode, algo: HashAlgo) -> Vec<u8> {
+    let mut file = fs::File::open(path).expect("failed to open file");
+    let mut hasher = Hasher::new(algo);
+    let mut buf = [0u8; BLOCK_SIZE];
+
+    match mode {
+        HashMode::Partial => {
+            let n = file.read(&mut buf).expect("failed to read file");
+            hasher.update(&buf[..n]);
+        }
+        HashMode::Full => loop {
+            let n = file.read(&mut buf).expect("failed to read file");
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        },
+    }
+
+    hasher.finalize()
+}
+
+/// Like `hash_file`, but consults `cache` first and populates it afterwards. A cache hit
+/// requires the canonical path, size, mtime, hash algorithm, and requested `mode` to all
+/// match the cached record, so a changed or truncated file, or a run with a different
+/// `--hash`, is always re-hashed instead of returning a digest from a different algorithm.
+fn hash_file_cached(
+    path: &Path,
+    mode: HashMode,
+    algo: HashAlgo,
+    cache: &RwLock<HashCache>,
+) -> Vec<u8> {
+    let metadata = fs::metadata(path).expect("failed to read file metadata");
+    let len = metadata.len();
+    let modified = metadata
+        .modified()
+        .expect("failed to read file modified time");
+    let canonical = fs::canonicalize(path).expect("failed to canonicalize path");
+
+    if let Some(cached) = cache.read().unwrap().entries.get(&canonical) {
+        if cached.len == len && cached.modified == modified && cached.algo == algo {
+            let existing = match mode {
+                HashMode::Partial => &cached.partial_digest,
+                HashMode::Full => &cached.full_digest,
+            };
+            if let Some(digest) = existing {
+                return digest.clone();
+            }
+        }
+    }
+
+    let digest = hash_file(path, mode, algo);
+
+    let mut cache = cache.write().unwrap();
+    let cached = cache.entries.entry(canonical).or_insert_with(|| CachedHash {
+        len,
+        modified,
+        algo,
+        partial_digest: None,
+        full_digest: None,
+    });
+
+    // The file changed, or a different hash algorithm was selected, since whatever was
+    // cached (if anything): drop the stale digests.
+    if cached.len != len || cached.modified != modified || cached.algo != algo {
+        cached.len = len;
+        cached.modified = modified;
+        cached.algo = algo;
+        cached.partial_digest = None;
+        cached.full_digest = None;
+    }
+
+    match mode {
+        HashMode::Partial => cached.partial_digest = Some(digest.clone()),
+        HashMode::Full => cached.full_digest = Some(digest.clone()),
+    }
+
+    digest
+}
+
+#[cfg(test)]
+mod hash_cache_tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_file_with_contents(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "cleanup-test-{}-{}-{}",
+            std::process::id(),
+            name,
+            contents.len()
+        ));
+        let mut file = fs::File::create(&path).expect("failed to create temp file");
+        file.write_all(contents).expect("failed to write temp file");
+        path
+    }
+
+    #[test]
+    fn partial_and_full_digests_are_cached_independently() {
+        let path = temp_file_with_contents("independent", &[1u8; BLOCK_SIZE * 2]);
+        let cache = RwLock::new(HashCache::default());
+
+        let partial = hash_file_cached(&path, HashMode::Partial, HashAlgo::Sha1, &cache);
+        let full = hash_file_cached(&path, HashMode::Full, HashAlgo::Sha1, &cache);
+
+        let canonical = fs::canonicalize(&path).unwrap();
+        let cached = cache.read().unwrap().entries.get(&canonical).unwrap().clone();
+
+        // Probing Full after Partial must not have evicted the Partial digest, and vice
+        // versa: this is exactly the bug where a single `mode` field made each probe
+        // overwrite the other's cached entry.
+        assert_eq!(cached.partial_digest.as_deref(), Some(partial.as_slice()));
+        assert_eq!(cached.full_digest.as_deref(), Some(full.as_slice()));
+
+        // Re-probing either mode now hits the cache and returns the same digest.
+        assert_eq!(
+            hash_file_cached(&path, HashMode::Partial, HashAlgo::Sha1, &cache),
+            partial
+        );
+        assert_eq!(
+            hash_file_cached(&path, HashMode::Full, HashAlgo::Sha1, &cache),
+            full
+        );
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn changed_file_invalidates_both_cached_digests() {
+        let path = temp_file_with_contents("invalidate", &[1u8; 32]);
+        let cache = RwLock::new(HashCache::default());
+
+        hash_file_cached(&path, HashMode::Partial, HashAlgo::Sha1, &cache);
+        hash_file_cached(&path, HashMode::Full, HashAlgo::Sha1, &cache);
+
+        // Same length, different contents, but force a distinct mtime so the cache can
+        // only notice the change via `modified`, not `len`.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(&path, [2u8; 32]).expect("failed to rewrite temp file");
+
+        let full = hash_file_cached(&path, HashMode::Full, HashAlgo::Sha1, &cache);
+        assert_eq!(full, hash_file(&path, HashMode::Full, HashAlgo::Sha1));
+
+        let partial = hash_file_cached(&path, HashMode::Partial, HashAlgo::Sha1, &cache);
+        assert_eq!(partial, hash_file(&path, HashMode::Partial, HashAlgo::Sha1));
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn switching_hash_algo_invalidates_the_cached_digest() {
+        let path = temp_file_with_contents("algo-switch", &[1u8; 32]);
+        let cache = RwLock::new(HashCache::default());
+
+        let sha1 = hash_file_cached(&path, HashMode::Full, HashAlgo::Sha1, &cache);
+
+        // A later run with a different --hash must not be served the other algorithm's
+        // cached digest: that would silently mix digests of different widths/algorithms
+        // within one comparison and miss real duplicates.
+        let xxh3 = hash_file_cached(&path, HashMode::Full, HashAlgo::Xxh3, &cache);
+        assert_eq!(xxh3, hash_file(&path, HashMode::Full, HashAlgo::Xxh3));
+        assert_ne!(sha1, xxh3);
+
+        fs::remove_file(&path).ok();
+    }
+}
+
+/// Which file(s) to keep from a group of confirmed-identical files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeepPolicy {
+    /// Keep the single oldest file, delete every other copy.
+    Oldest,
+    /// Keep the single newest file, delete every other copy.
+    Newest,
+    /// Delete only the oldest copy, keeping everything else.
+    OneOldest,
+    /// Delete only the newest copy, keeping everything else.
+    OneNewest,
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "newest" => Ok(KeepPolicy::Newest),
+            "one-oldest" => Ok(KeepPolicy::OneOldest),
+            "one-newest" => Ok(KeepPolicy::OneNewest),
+            other => Err(format!("unknown keep policy: {}", other)),
+        }
+    }
+}
+
+/// A comma-separated list of extension patterns (regexes, matched case-insensitively and
+/// anchored to the whole extension) parsed from a single `--allowed-extensions` /
+/// `--excluded-extensions` argument.
+#[derive(Debug, Clone)]
+struct ExtensionPatterns(Vec<Regex>);
+
+impl FromStr for ExtensionPatterns {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                Regex::new(&format!("(?i)^(?:{})$", pattern))
+                    .map_err(|e| format!("invalid extension pattern {:?}: {}", pattern, e))
+            })
+            .collect::<std::result::Result<_, _>>()
+            .map(ExtensionPatterns)
+    }
+}
+
+impl ExtensionPatterns {
+    fn matches(&self, extension: &str) -> bool {
+        self.0.iter().any(|pattern| pattern.is_match(extension))
+    }
+}
+
+/// Streams both files in `BLOCK_SIZE` chunks and returns whether their contents are
+/// byte-for-byte identical. Used to confirm a digest match before deleting anything when
+/// the selected hash algorithm (`Xxh3`) is too narrow to trust on its own.
+fn files_equal(a: &Path, b: &Path) -> bool {
+    let mut file_a = match fs::File::open(a) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut file_b = match fs::File::open(b) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut buf_a = [0u8; BLOCK_SIZE];
+    let mut buf_b = [0u8; BLOCK_SIZE];
+
+    loop {
+        let read_a = file_a.read(&mut buf_a).expect("failed to read file");
+        let read_b = file_b.read(&mut buf_b).expect("failed to read file");
+
+        if read_a != read_b || buf_a[..read_a] != buf_b[..read_b] {
+            return false;
+        }
+        if read_a == 0 {
+            return true;
+        }
+    }
+}
+
+/// Returns `metadata`'s creation time, falling back to its modification time when the
+/// underlying filesystem doesn't record birth times (`created()` returns `Err` there, e.g.
+/// most Linux filesystems before ext4/statx support). Retention ordering only needs *some*
+/// consistent timestamp, so this keeps otherwise-valid files from panicking the program.
+fn created_or_modified(metadata: &fs::Metadata) -> SystemTime {
+    metadata
+        .created()
+        .or_else(|_| metadata.modified())
+        .expect("failed to read file modified timestamp")
+}
+
+/// Applies `options.keep` to a full group of confirmed-identical files, deleting the
+/// entries the policy selects and (optionally) symlinking or hard-linking them to the
+/// retained file.
+/// Returns the paths of members that were left untouched despite being selected for
+/// deletion (already hard-linked to the retained file, or an unconfirmed `Xxh3` match) so
+/// callers reporting the group can exclude them too.
+fn apply_keep_policy(
+    members: &[&HashedEntry],
+    deleted_file_count: &AtomicUsize,
+    options: &Opt,
+) -> HashSet<PathBuf> {
+    let mut skipped = HashSet::new();
+    let mut sorted: Vec<&HashedEntry> = members.to_vec();
+    sorted.sort_by_key(|hashed| {
+        created_or_modified(&hashed.entry.metadata().expect("failed to read entry metadata"))
+    });
+
+    let delete_indices: Vec<usize> = match options.keep {
+        KeepPolicy::Oldest => (1..sorted.len()).collect(),
+        KeepPolicy::Newest => (0..sorted.len() - 1).collect(),
+        KeepPolicy::OneOldest => vec![0],
+        KeepPolicy::OneNewest => vec![sorted.len() - 1],
+    };
+
+    let retained_entry = sorted
+        .iter()
+        .enumerate()
+        .find(|(index, _)| !delete_indices.contains(index))
+        .map(|(_, hashed)| *hashed)
+        .expect("a duplicate group must retain at least one file");
+    let retained = retained_entry.entry.path();
+
+    for index in delete_indices {
+        let hashed = sorted[index];
+        let path = hashed.entry.path();
+
+        // Already hard-linked to the retained file: there's nothing to free or relink.
+        if hashed.dev_ino.is_some() && hashed.dev_ino == retained_entry.dev_ino {
+            skipped.insert(path);
+            continue;
+        }
+
+        // Xxh3's 64-bit digest can collide; a match is only trusted once the bytes
+        // themselves are confirmed equal.
+        if options.hash == HashAlgo::Xxh3 && !files_equal(&path, &retained) {
+            eprintln!(
+                "{:?} shares an xxh3 digest with {:?} but its contents differ; skipping",
+                path, retained
+            );
+            skipped.insert(path);
+            continue;
+        }
+
+        deleted_file_count.fetch_add(1, atomic::Ordering::Relaxed);
+
+        if !options.dry {
+            fs::remove_file(&path).expect("failed to remove file");
+            #[cfg(unix)]
+            {
+                if options.symlink {
+                    std::os::unix::fs::symlink(&retained, &path).unwrap_or_else(|e| {
+                        panic!(
+                            "failed to make a symlink from {:?} to {:?}: {}",
+                            path, retained, e
+                        )
+                    });
+                } else if options.hardlink {
+                    // Hard links can't cross filesystems (EXDEV), a realistic case for
+                    // same-size/same-hash files under different mounts; the original is
+                    // already gone at this point, so fall back to a symlink rather than
+                    // aborting the whole run on otherwise-valid input.
+                    if let Err(e) = fs::hard_link(&retained, &path) {
+                        eprintln!(
+                            "failed to hard link {:?} to {:?} ({}); falling back to a symlink",
+                            path, retained, e
+                        );
+                        if let Err(e) = std::os::unix::fs::symlink(&retained, &path) {
+                            eprintln!(
+                                "failed to symlink {:?} to {:?} either ({}); {:?} is now gone",
+                                path, retained, e, path
+                            );
+                        }
+                    }
+                }
+            }
+        } else {
+            eprintln!("{:?} is a duplicate", path);
+        }
+    }
+
+    skipped
+}
+
+/// Machine-readable output format for the discovered duplicate groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            other => Err(format!("unknown output format: {}", other)),
+        }
+    }
+}
+
+/// One file within a duplicate group, along with the timestamp used to order the group.
+#[derive(Debug, Serialize)]
+struct DuplicateFile {
+    path: PathBuf,
+    created: SystemTime,
+}
+
+/// A confirmed group of identical files, reported regardless of which ones `--keep` deletes.
+#[derive(Debug, Serialize)]
+struct DuplicateGroup {
+    size: u64,
+    hash: String,
+    files: Vec<DuplicateFile>,
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Quotes `field` per RFC 4180: wrapped in double quotes, with any embedded double quote
+/// doubled, whenever it contains a comma, double quote, or newline that would otherwise
+/// break the CSV row.
+fn csv_field(field: &str) -> String {
+    if field.contains(['"', ',', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -23,22 +569,111 @@ struct Opt {
     #[structopt(long = "symlink")]
     symlink: bool,
 
+    /// Hard-link duplicates to a single file (oldest file by default) instead of symlinking
+    #[structopt(long = "hardlink")]
+    hardlink: bool,
+
+    /// Hash algorithm used to compare file contents (sha1, blake3, xxh3). Defaults to xxh3,
+    /// the fastest and only non-cryptographic option of the three, since its narrow 64-bit
+    /// digest is never trusted on its own: a full byte comparison is done before deleting
+    /// anything whose duplicate status rests on an xxh3 match
+    #[structopt(long = "hash", default_value = "xxh3")]
+    hash: HashAlgo,
+
+    /// Path to the on-disk hash cache
+    #[structopt(long = "cache", parse(from_os_str), default_value = ".cleanup-cache.json")]
+    cache: PathBuf,
+
+    /// Disable the on-disk hash cache
+    #[structopt(long = "no-cache")]
+    no_cache: bool,
+
+    /// Only consider files whose extension matches one of these comma-separated patterns
+    #[structopt(long = "allowed-extensions")]
+    allowed_extensions: Option<ExtensionPatterns>,
+
+    /// Skip files whose extension matches one of these comma-separated patterns
+    #[structopt(long = "excluded-extensions")]
+    excluded_extensions: Option<ExtensionPatterns>,
+
+    /// Directory to exclude from the scan (may be given multiple times)
+    #[structopt(long = "exclude", parse(from_os_str))]
+    exclude: Vec<PathBuf>,
+
+    /// Minimum file size, in bytes, to consider; smaller files are ignored
+    #[structopt(long = "min-size", default_value = "0")]
+    min_size: u64,
+
+    /// Retention policy for each group of duplicates (oldest, newest, one-oldest, one-newest)
+    #[structopt(long = "keep", default_value = "oldest")]
+    keep: KeepPolicy,
+
+    /// Emit the discovered duplicate groups as machine-readable output (json, csv)
+    #[structopt(long = "format")]
+    format: Option<OutputFormat>,
+
     /// Input directory
     #[structopt(parse(from_os_str))]
     input: PathBuf,
 }
 
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
+    opt.exclude = opt
+        .exclude
+        .iter()
+        .map(|dir| fs::canonicalize(dir).expect("failed to canonicalize excluded directory"))
+        .collect();
     let deleted_count = atomic::AtomicUsize::new(0);
+    let cache = RwLock::new(if opt.no_cache {
+        HashCache::default()
+    } else {
+        HashCache::load(&opt.cache)
+    });
+    let groups: Mutex<Vec<DuplicateGroup>> = Mutex::new(Vec::new());
 
     let input = opt.input.clone();
     rayon::scope(|s| {
         s.spawn(|s| {
-            process_directory(input, &deleted_count, &opt, s);
+            process_directory(input, &deleted_count, &opt, &cache, &groups, s);
         });
     });
 
+    if !opt.no_cache {
+        cache.into_inner().unwrap().save(&opt.cache);
+    }
+
+    if let Some(format) = opt.format {
+        let groups = groups.into_inner().unwrap();
+        match format {
+            OutputFormat::Json => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&groups).expect("failed to serialize groups")
+                );
+            }
+            OutputFormat::Csv => {
+                println!("size,hash,path,created");
+                for group in &groups {
+                    for file in &group.files {
+                        let created = file
+                            .created
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .expect("file created before the Unix epoch")
+                            .as_secs();
+                        println!(
+                            "{},{},{},{}",
+                            group.size,
+                            group.hash,
+                            csv_field(&file.path.to_string_lossy()),
+                            created
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     if opt.dry {
         println!(
             "Would have deleted {} files",
@@ -56,11 +691,13 @@ fn main() -> Result<()> {
 
 /// Processes the files in the given directory. When a subdirectory is encountered a new
 /// task is spawned to handle that directory.
-fn process_directory<'a, 'b>(
+fn process_directory<'a>(
     dir: PathBuf,
     deleted_file_count: &'a AtomicUsize,
     options: &'a Opt,
-    scope: &'b Scope<'a>,
+    cache: &'a RwLock<HashCache>,
+    groups: &'a Mutex<Vec<DuplicateGroup>>,
+    scope: &Scope<'a>,
 ) {
     let file_sizes: RwLock<HashMap<u64, Vec<&fs::DirEntry>>> = RwLock::new(HashMap::new());
 
@@ -74,14 +711,7 @@ fn process_directory<'a, 'b>(
         let a_metadata = a.metadata().expect("failed to read entry metadata");
         let b_metadata = b.metadata().expect("failed to read entry metadata");
 
-        let a_created = a_metadata
-            .created()
-            .expect("failed to read file created timestamp");
-        let b_created = b_metadata
-            .created()
-            .expect("failed to read file created timestamp");
-
-        a_created.cmp(&b_created)
+        created_or_modified(&a_metadata).cmp(&created_or_modified(&b_metadata))
     });
 
     entries.par_iter().for_each(|entry| {
@@ -90,10 +720,33 @@ fn process_directory<'a, 'b>(
 
         let file_type = entry.file_type().expect("failed to get file type");
         if file_type.is_dir() && !file_type.is_symlink() {
+            let canonical_path = fs::canonicalize(&path).expect("failed to canonicalize directory");
+            if options.exclude.contains(&canonical_path) {
+                return;
+            }
+
             scope.spawn(move |s| {
-                process_directory(path, deleted_file_count, options, s);
+                process_directory(path, deleted_file_count, options, cache, groups, s);
             });
         } else {
+            if metadata.len() < options.min_size {
+                return;
+            }
+
+            let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+            if let Some(allowed) = &options.allowed_extensions {
+                if !allowed.matches(extension) {
+                    return;
+                }
+            }
+
+            if let Some(excluded) = &options.excluded_extensions {
+                if excluded.matches(extension) {
+                    return;
+                }
+            }
+
             file_sizes
                 .write()
                 .unwrap()
@@ -109,38 +762,83 @@ fn process_directory<'a, 'b>(
         .unwrap()
         .par_iter()
         .filter(|(_size, entries)| entries.len() > 1)
-        .for_each(|(_size, entries)| {
-            let mut hashes = HashMap::new();
+        .for_each(|(size, entries)| {
+            // Cheap pre-filter: group by a hash of just the first block so files that
+            // diverge early never have to be read in full.
+            let mut partial_groups: HashMap<Vec<u8>, Vec<HashedEntry>> = HashMap::new();
             for entry in entries {
-                let mut hasher = Sha1::new();
                 let path = entry.path();
-                // process input message
-                hasher.update(fs::read(&path).expect("failed to read file").as_slice());
-
-                // acquire hash digest in the form of GenericArray,
-                // which in this case ivalent to [u8; 20]
-                let result = hasher.finalize();
-                if let Some(target_file) = hashes.get(&result) {
-                    deleted_file_count.fetch_add(1, atomic::Ordering::Relaxed);
-
-                    if !options.dry {
-                        fs::remove_file(&path).expect("failed to remove file");
-                        #[cfg(unix)]
-                        {
-                            if options.symlink {
-                                std::os::unix::fs::symlink(&target_file, &path).unwrap_or_else(|e| {
-                                    panic!(
-                                        "failed to make a symlink from {:?} to {:?}: {}",
-                                        path, target_file, e
-                                    )
-                                });
-                            }
-                        }
+                let digest = hash_file_cached(&path, HashMode::Partial, options.hash, cache);
+                partial_groups
+                    .entry(digest.clone())
+                    .or_default()
+                    .push(HashedEntry {
+                        entry,
+                        digest,
+                        dev_ino: dev_ino(&path),
+                    });
+            }
+
+            for (_digest, mut group) in partial_groups {
+                // A unique partial hash means a unique file; skip it entirely.
+                if group.len() < 2 {
+                    continue;
+                }
+
+                // The partial hash still collides, so these entries need a full read.
+                for hashed in &mut group {
+                    hashed.digest =
+                        hash_file_cached(&hashed.entry.path(), HashMode::Full, options.hash, cache);
+                }
+
+                // Group by the confirmed full hash, then apply the retention policy over
+                // each complete duplicate set rather than deleting as matches are found.
+                let mut full_groups: HashMap<Vec<u8>, Vec<&HashedEntry>> = HashMap::new();
+                for hashed in &group {
+                    full_groups
+                        .entry(hashed.digest.clone())
+                        .or_default()
+                        .push(hashed);
+                }
+
+                for (digest, members) in full_groups {
+                    if members.len() < 2 {
+                        continue;
+                    }
+
+                    // Capture each member's reportable state before running the keep policy:
+                    // by the time it returns, every deleted member's path is gone and its
+                    // metadata can no longer be read.
+                    let report_files: Vec<DuplicateFile> = if options.format.is_some() {
+                        members
+                            .iter()
+                            .map(|hashed| DuplicateFile {
+                                path: hashed.entry.path(),
+                                created: created_or_modified(
+                                    &hashed.entry.metadata().expect("failed to read entry metadata"),
+                                ),
+                            })
+                            .collect()
                     } else {
-                        eprintln!("{:?} is a duplicate", path);
+                        Vec::new()
+                    };
+
+                    let skipped = apply_keep_policy(&members, deleted_file_count, options);
+
+                    // Report only the files that were actually acted on (deleted or kept);
+                    // members `apply_keep_policy` left untouched (already hard-linked to the
+                    // retained file, or an xxh3 match that failed the byte comparison) were
+                    // never really duplicates from the user's point of view.
+                    if options.format.is_some() {
+                        groups.lock().unwrap().push(DuplicateGroup {
+                            size: *size,
+                            hash: hex_encode(&digest),
+                            files: report_files
+                                .into_iter()
+                                .filter(|file| !skipped.contains(&file.path))
+                                .collect(),
+                        });
                     }
-                } else {
-                    hashes.insert(result, path);
                 }
             }
         });